@@ -15,8 +15,8 @@ Como ler este arquivo:
 /* Imports do Axum: trazem tipos e funções para lidar com rotas, extração de parâmetros,
    montagem de respostas e escolha de métodos HTTP (GET/POST). */
 use axum::{
-    extract::{Path, State},      // Path extrai valores da URL (ex.: {id}); State injeta objetos compartilhados (ex.: pool do banco).
-    http::StatusCode,            // Enum com códigos HTTP (200, 404, 422, 500...).
+    extract::{FromRef, Path, State}, // Path extrai valores da URL (ex.: {id}); State injeta pedaços do AppState; FromRef decompõe o AppState.
+    http::{HeaderMap, StatusCode}, // HeaderMap dá acesso aos cabeçalhos da requisição (ex.: Idempotency-Key); StatusCode são os códigos HTTP (200, 404, 422, 500...).
     response::Response,          // Tipo de resposta HTTP bruta (permite montar manualmente cabeçalhos e corpo).
     routing::{get, post},        // Helpers para declarar rotas GET e POST.
     Json, Router,                // Json extrai/serializa JSON; Router registra rotas e estado da aplicação.
@@ -30,11 +30,180 @@ use axum::{
    - SocketAddr/TcpListener: definem onde o servidor TCP escuta. */
 use axum::body::Body;
 use axum::http::header::CONTENT_TYPE;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
 use serde::Deserialize;
-use sqlx::{postgres::PgPoolOptions, PgPool, Postgres}; // Postgres aqui é o "dialeto" usado pelos genéricos do SQLx.
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool, Postgres, // Postgres aqui é o "dialeto" usado pelos genéricos do SQLx.
+};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::net::TcpListener;
 
+/* ============================== CONFIGURAÇÃO DA APLICAÇÃO ===============================
+   Antes, a faixa válida de ids de conta (1..=5) era uma constante mágica espalhada pelos
+   handlers, e os ajustes de pool/sessão só existiam como variáveis locais de `main`. Agora
+   tudo isso vira `Config`, carregado uma única vez na inicialização e compartilhado via
+   AppState — os handlers leem a política em vez de embutir os números. */
+struct Config {
+    min_account_id: u8,       // Menor id de conta aceito (era o "1" fixo em 1..=5).
+    max_account_id: u8,       // Maior id de conta aceito (era o "5" fixo em 1..=5).
+    pg_min_conns: u32,        // Tamanho mínimo do pool de conexões.
+    pg_max_conns: u32,        // Tamanho máximo do pool de conexões.
+    synchronous_commit: String, // Valor aplicado em `SET synchronous_commit = '...'` a cada conexão nova.
+}
+
+impl Config {
+    fn from_env() -> Self {
+        Self {
+            min_account_id: std::env::var("MIN_ACCOUNT_ID").ok().and_then(|s| s.parse().ok()).unwrap_or(1),
+            max_account_id: std::env::var("MAX_ACCOUNT_ID").ok().and_then(|s| s.parse().ok()).unwrap_or(5),
+            pg_min_conns: std::env::var("PG_MIN").ok().and_then(|s| s.parse().ok()).unwrap_or(5),
+            pg_max_conns: std::env::var("PG_MAX").ok().and_then(|s| s.parse().ok()).unwrap_or(30),
+            synchronous_commit: Self::validate_synchronous_commit(
+                std::env::var("DB_SYNCHRONOUS_COMMIT").unwrap_or_else(|_| "off".to_string()),
+            ),
+        }
+    }
+
+    /// O valor vira um literal interpolado em `SET synchronous_commit = '...'` (o Postgres não
+    /// aceita bind parameter em SET), então validamos contra a lista fechada de valores que o
+    /// Postgres aceita antes de montar a string — evitando injetar SQL arbitrário via env var.
+    fn validate_synchronous_commit(value: String) -> String {
+        match value.as_str() {
+            "on" | "off" | "local" | "remote_write" | "remote_apply" => value,
+            _ => "off".to_string(),
+        }
+    }
+
+    /// Substitui o antigo truque `uid.wrapping_sub(1) > 4`: como os limites agora vêm de
+    /// configuração (não são mais `1` e `5` fixos), a comparação direta é a forma clara de
+    /// checar a faixa sem reintroduzir aritmética wrapping sobre limites variáveis.
+    fn account_id_in_range(&self, id: u8) -> bool {
+        id >= self.min_account_id && id <= self.max_account_id
+    }
+}
+
+/* ============================== OBSERVABILIDADE (PROMETHEUS) ============================
+   As métricas abaixo deixaram de ser estáticas globais e viraram um handle (`Metrics`) criado
+   uma única vez em `main` e carregado no AppState — assim qualquer handler novo que precise
+   publicar uma métrica só precisa pedir `State<Arc<Metrics>>`, sem depender do registry global
+   do processo.
+   - requests_total: contador por (rota, status), útil para taxa de erro (ex.: % de 422).
+   - request_duration_seconds: histograma de latência por rota; os buckets vão de 0,1 ms a
+     50 ms porque os handlers aqui respondem bem rápido.
+   - requests_in_flight: gauge de requisições em andamento (profundidade de concorrência real).
+   - db_pool_size / db_pool_idle: gauges amostradas do PgPool a cada scrape de /metrics. */
+struct Metrics {
+    registry: Registry,
+    requests_total: IntCounterVec,
+    request_duration_seconds: HistogramVec,
+    requests_in_flight: IntGauge,
+    db_pool_size: IntGauge,
+    db_pool_idle: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new(); // Registry próprio (em vez do default global) para viver dentro do AppState.
+
+        let requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "Total de requisições HTTP, particionado por rota e status"),
+            &["route", "status"],
+        )
+        .unwrap();
+        registry.register(Box::new(requests_total.clone())).unwrap();
+
+        let request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Duração das requisições HTTP em segundos, particionado por rota",
+            )
+            .buckets(vec![0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05]),
+            &["route"],
+        )
+        .unwrap();
+        registry.register(Box::new(request_duration_seconds.clone())).unwrap();
+
+        let requests_in_flight =
+            IntGauge::new("http_requests_in_flight", "Quantidade de requisições HTTP em andamento neste instante").unwrap();
+        registry.register(Box::new(requests_in_flight.clone())).unwrap();
+
+        let db_pool_size = IntGauge::new("db_pool_size", "Conexões atualmente abertas no pool do Postgres").unwrap();
+        registry.register(Box::new(db_pool_size.clone())).unwrap();
+
+        let db_pool_idle =
+            IntGauge::new("db_pool_idle", "Conexões ociosas (não emprestadas) no pool do Postgres").unwrap();
+        registry.register(Box::new(db_pool_idle.clone())).unwrap();
+
+        Self {
+            registry,
+            requests_total,
+            request_duration_seconds,
+            requests_in_flight,
+            db_pool_size,
+            db_pool_idle,
+        }
+    }
+
+    /// Registra latência e status final de uma requisição para a rota informada.
+    fn record(&self, route: &str, status: StatusCode, elapsed: std::time::Duration) {
+        self.request_duration_seconds.with_label_values(&[route]).observe(elapsed.as_secs_f64());
+        self.requests_total.with_label_values(&[route, status.as_str()]).inc();
+    }
+}
+
+/* ============================== ESTADO COMPARTILHADO (AppState) ========================
+   AppState substitui o antigo `State<PgPool>` cru por um contêiner clonável com tudo que os
+   handlers precisam: o pool, a configuração e o handle de métricas. Implementar `FromRef`
+   para cada campo deixa os handlers existentes inalterados (continuam extraindo
+   `State<PgPool>` normalmente) e permite que handlers novos peçam `State<Arc<Config>>` ou
+   `State<Arc<Metrics>>` sem precisar de parâmetros extras em cada rota. */
+#[derive(Clone)]
+struct AppState {
+    pool: PgPool,
+    cfg: Arc<Config>,
+    metrics: Arc<Metrics>,
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.cfg.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Metrics> {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+/* InFlightGuard: incrementa o gauge de "em andamento" ao ser criado e decrementa sozinho
+   quando sai de escopo (via Drop), mesmo em um retorno antecipado (early return). Isso evita
+   ter que lembrar de "descontar" manualmente em cada ponto de saída do handler. Agora guarda
+   um clone do IntGauge (em vez de uma estática global), já que a métrica mora no AppState. */
+struct InFlightGuard(IntGauge);
+
+impl InFlightGuard {
+    fn new(gauge: IntGauge) -> Self {
+        gauge.inc();                           // Mais uma requisição entrando.
+        InFlightGuard(gauge)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.dec();                          // Requisição saiu (sucesso, erro ou early return).
+    }
+}
+
 /* ============================== BLOCO DE SQL DE SUPORTE =================================
    As constantes abaixo contêm instruções SQL que são executadas na inicialização. Isso deixa
    o banco “pronto” para o teste: cria um índice útil e duas funções PL/pgSQL (get_extrato e
@@ -133,6 +302,146 @@ END;
 $$ LANGUAGE plpgsql;
 "#; // A CTE evita condições de corrida e mantém tudo em uma operação transacional no servidor.
 
+/* Função process_transactions: aplica um LOTE de transações (crédito/débito) para UMA conta
+   em uma única ida ao banco. Em vez de chamar process_transaction N vezes, o Rust monta
+   três arrays paralelos (valores, tipos, descrições) e o Postgres percorre esse lote com
+   `unnest(...) WITH ORDINALITY`, preservando a ordem de chegada.
+   - O saldo é lido uma única vez com `FOR UPDATE` (trava a linha da conta) e atualizado em
+     memória a cada item do laço; só existe um UPDATE físico no final, então o lote inteiro
+     roda dentro de uma única transação atômica da função.
+   - Um débito que estouraria o limite é marcado como rejeitado, mas NÃO aborta o lote: os
+     itens seguintes continuam sendo avaliados contra o saldo corrente.
+   - O retorno inclui o saldo/limite finais e um array com o status de cada item, na mesma
+     ordem em que foi enviado (campo `ordem`, 1-based, vindo de WITH ORDINALITY). */
+const CREATE_TRANSACTIONS_BATCH_FUNCTION_SQL: &str = r#"
+CREATE OR REPLACE FUNCTION process_transactions(
+    p_account_id INT,
+    p_valores INT[],
+    p_tipos CHAR[],
+    p_descricoes TEXT[]
+)
+RETURNS JSON AS $$
+DECLARE
+    item RECORD;
+    current_balance INT;
+    current_limit INT;
+    novo_saldo INT;
+    aplicado BOOLEAN;
+    resultados JSON[] := '{}';
+BEGIN
+    SELECT balance, account_limit
+    INTO current_balance, current_limit
+    FROM accounts
+    WHERE id = p_account_id
+    FOR UPDATE;
+
+    IF NOT FOUND THEN
+        RETURN '{"error": 1}'::json;
+    END IF;
+
+    FOR item IN
+        SELECT valor, tipo, descricao, ord
+        FROM unnest(p_valores, p_tipos, p_descricoes) WITH ORDINALITY AS t(valor, tipo, descricao, ord)
+        ORDER BY ord
+    LOOP
+        IF item.tipo = 'c' THEN
+            novo_saldo := current_balance + item.valor;
+            aplicado := TRUE;
+        ELSE
+            novo_saldo := current_balance - item.valor;
+            aplicado := (novo_saldo >= -current_limit);
+        END IF;
+
+        IF aplicado THEN
+            current_balance := novo_saldo;
+            INSERT INTO transactions (account_id, amount, type, description)
+            VALUES (p_account_id, item.valor, item.tipo, item.descricao);
+            resultados := resultados || json_build_object('ordem', item.ord, 'status', 'aplicada');
+        ELSE
+            resultados := resultados || json_build_object('ordem', item.ord, 'status', 'rejeitada');
+        END IF;
+    END LOOP;
+
+    UPDATE accounts SET balance = current_balance WHERE id = p_account_id;
+
+    RETURN json_build_object(
+        'saldo', current_balance,
+        'limite', current_limit,
+        'resultados', array_to_json(resultados)
+    );
+END;
+$$ LANGUAGE plpgsql;
+"#; // FOR UPDATE serializa lotes concorrentes na mesma conta; o UPDATE único no fim minimiza escrita.
+
+/* ============================== IDEMPOTÊNCIA DE TRANSAÇÕES ==============================
+   Tabela que guarda, por chave de idempotência, a conta associada e a resposta já calculada.
+   Um cliente que reenvia o mesmo POST (retry de rede, timeout no lado dele etc.) com o mesmo
+   cabeçalho `Idempotency-Key` deve receber de volta a MESMA resposta, sem aplicar a transação
+   de novo. */
+const CREATE_IDEMPOTENCY_KEYS_TABLE_SQL: &str = r#"
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    account_id INT NOT NULL,
+    response JSON NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+"#; // A chave é a própria Idempotency-Key recebida no cabeçalho; account_id guarda a conta dona da chave (replay em outra conta → {"error": 2}).
+
+/* Função process_transaction_idempotent: embrulha process_transaction com suporte a replay.
+   - Primeiro tenta "reservar" a chave com `INSERT ... ON CONFLICT (key) DO NOTHING RETURNING key`:
+     se a chave já existe, o INSERT não insere nada e RETURNING não devolve linha, então sabemos
+     que é um replay.
+   - Numa corrida entre duas requisições com a mesma chave, o INSERT da segunda fica bloqueado
+     até a primeira commitar (ou abortar); ao desbloquear, ela enxerga a resposta já gravada.
+   - Isso tudo roda dentro da própria função, ou seja, em uma única transação de banco: inserir
+     a chave, aplicar o crédito/débito e gravar a resposta final são atômicos entre si.
+   - Como `Idempotency-Key` é escolhida pelo cliente (não escopada por conta), o mesmo valor
+     pode ser reenviado por engano para uma conta diferente da que gerou a chave. Nesse caso o
+     replay NÃO deve devolver a resposta da primeira conta: comparamos `account_id` e, se não
+     bater, devolvemos `{"error": 2}` (conflito de chave) em vez de `existing_response`. */
+const CREATE_IDEMPOTENT_TRANSACTION_FUNCTION_SQL: &str = r#"
+CREATE OR REPLACE FUNCTION process_transaction_idempotent(
+    p_account_id INT,
+    p_amount INT,
+    p_type CHAR,
+    p_description VARCHAR(10),
+    p_idempotency_key TEXT
+)
+RETURNS JSON AS $$
+DECLARE
+    reserved_key TEXT;
+    existing_account_id INT;
+    existing_response JSON;
+    v_response JSON;
+BEGIN
+    INSERT INTO idempotency_keys (key, account_id, response)
+    VALUES (p_idempotency_key, p_account_id, 'null'::json)
+    ON CONFLICT (key) DO NOTHING
+    RETURNING key INTO reserved_key;
+
+    IF reserved_key IS NULL THEN
+        SELECT account_id, response INTO existing_account_id, existing_response
+        FROM idempotency_keys
+        WHERE key = p_idempotency_key;
+
+        IF existing_account_id <> p_account_id THEN
+            RETURN '{"error": 2}'::json; -- Mesma chave usada para outra conta: recusa em vez de responder pela conta errada.
+        END IF;
+
+        RETURN existing_response; -- Replay: devolve exatamente o que foi respondido da primeira vez.
+    END IF;
+
+    v_response := process_transaction(p_account_id, p_amount, p_type, p_description);
+
+    UPDATE idempotency_keys
+    SET response = v_response
+    WHERE key = p_idempotency_key;
+
+    RETURN v_response;
+END;
+$$ LANGUAGE plpgsql;
+"#; // INSERT ... ON CONFLICT DO NOTHING RETURNING é o mecanismo que decide "primeira vez vs. replay".
+
 /* ============================== MODELO DE ENTRADA (JSON) ===============================
    Define como o corpo do POST /clientes/{id}/transacoes deve chegar. O Axum + Serde vai
    converter JSON → struct automaticamente, e depois faremos validações simples no handler.
@@ -159,6 +468,108 @@ struct TxPayload {
      prevenindo injeção de SQL. */
 const Q_GET_EXTRATO: &str = "SELECT get_extrato($1)::text";
 const Q_PROCESS_TX:  &str = "SELECT process_transaction($1, $2, $3, $4)::text";
+const Q_PROCESS_TX_LOTE: &str = "SELECT process_transactions($1, $2, $3::char[], $4)::text";
+const Q_PROCESS_TX_IDEMPOTENT: &str = "SELECT process_transaction_idempotent($1, $2, $3, $4, $5)::text";
+
+/* ============================== CLASSIFICAÇÃO DE ERROS DE BANCO =========================
+   Antes, todo `sqlx::Error` virava um 500 genérico. Aqui olhamos o SQLSTATE retornado pelo
+   Postgres (`err.as_database_error().and_then(|e| e.code())`) e decidimos por classe:
+   - 40001 (serialization_failure) e 40P01 (deadlock_detected): erros transitórios, esperados
+     sob alta concorrência com `synchronous_commit='off'`; são seguros para repetir e por isso
+     voltam como retentáveis (ver `exec_process_transaction`).
+   - 23514/23505 (check/unique violation): erro de domínio do cliente → 422.
+   - Classe 08xxx (connection_exception e afins): problema de conectividade → 503, para que
+     orquestradores façam backoff em vez de tratar o nó como definitivamente fora do ar.
+   - Qualquer outro código, ou erro sem SQLSTATE (ex.: falha de I/O): 500 genérico.
+   O segundo elemento da tupla indica se vale a pena tentar de novo a mesma operação. */
+fn classify(err: &sqlx::Error) -> (StatusCode, bool) {
+    let Some(db_err) = err.as_database_error() else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, false); // Sem SQLSTATE (ex.: erro de conexão do driver) → 500, sem retry.
+    };
+
+    match db_err.code().as_deref() {
+        Some("40001") | Some("40P01") => (StatusCode::INTERNAL_SERVER_ERROR, true), // serialization_failure / deadlock_detected.
+        Some("23514") | Some("23505") => (StatusCode::UNPROCESSABLE_ENTITY, false), // check_violation / unique_violation.
+        Some(code) if code.starts_with("08") => (StatusCode::SERVICE_UNAVAILABLE, false), // classe connection_exception.
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, false), // Qualquer outro SQLSTATE: 500 sem retry.
+    }
+}
+
+/* Backoff exponencial (em microssegundos) entre tentativas de process_transaction após um
+   erro retentável. O array também define o número máximo de retries: uma tentativa inicial
+   mais len(RETRY_BACKOFFS_US) repetições, todas dentro do mesmo handler HTTP. */
+const RETRY_BACKOFFS_US: [u64; 3] = [50, 200, 800];
+
+/* exec_process_transaction: roda a query de process_transaction com retry automático quando
+   o erro é classificado como retentável (serialization_failure/deadlock_detected). Centraliza
+   essa lógica para que o handler HTTP só precise tratar o resultado final. */
+async fn exec_process_transaction(
+    pool: &PgPool,
+    id: i32,
+    valor: i32,
+    tipo: &str,
+    descricao: &str,
+) -> Result<String, sqlx::Error> {
+    let mut attempt = 0usize;
+    loop {
+        match sqlx::query_scalar::<Postgres, String>(Q_PROCESS_TX)
+            .persistent(true)                  // Prepared statement persistente ajuda em cenários de alta repetição.
+            .bind(id)                           // p_account_id.
+            .bind(valor)                        // p_amount.
+            .bind(tipo)                         // p_type ('c' ou 'd').
+            .bind(descricao)                    // p_description.
+            .fetch_one(pool)
+            .await
+        {
+            Ok(body) => return Ok(body),        // Sucesso: devolve o JSON vindo do banco.
+            Err(err) => {
+                let (_, retryable) = classify(&err); // Só olhamos se é retentável; o status final é recalculado depois.
+                if !retryable || attempt >= RETRY_BACKOFFS_US.len() {
+                    return Err(err);            // Esgotou as tentativas, ou o erro não é transitório: propaga.
+                }
+                tokio::time::sleep(std::time::Duration::from_micros(RETRY_BACKOFFS_US[attempt])).await; // Backoff curto.
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/* exec_process_transaction_idempotent: mesma ideia de exec_process_transaction, mas chamando
+   process_transaction_idempotent com a chave de idempotência recebida no cabeçalho HTTP. O
+   retry por erro transitório continua valendo aqui: reenviar a mesma chave em caso de
+   serialization_failure/deadlock é seguro, pois a função SQL é a mesma operação idempotente. */
+async fn exec_process_transaction_idempotent(
+    pool: &PgPool,
+    id: i32,
+    valor: i32,
+    tipo: &str,
+    descricao: &str,
+    idempotency_key: &str,
+) -> Result<String, sqlx::Error> {
+    let mut attempt = 0usize;
+    loop {
+        match sqlx::query_scalar::<Postgres, String>(Q_PROCESS_TX_IDEMPOTENT)
+            .persistent(true)                  // Prepared statement persistente ajuda em cenários de alta repetição.
+            .bind(id)                           // p_account_id.
+            .bind(valor)                        // p_amount.
+            .bind(tipo)                         // p_type ('c' ou 'd').
+            .bind(descricao)                    // p_description.
+            .bind(idempotency_key)              // p_idempotency_key.
+            .fetch_one(pool)
+            .await
+        {
+            Ok(body) => return Ok(body),        // Sucesso (primeira vez ou replay): devolve o JSON vindo do banco.
+            Err(err) => {
+                let (_, retryable) = classify(&err); // Só olhamos se é retentável; o status final é recalculado depois.
+                if !retryable || attempt >= RETRY_BACKOFFS_US.len() {
+                    return Err(err);            // Esgotou as tentativas, ou o erro não é transitório: propaga.
+                }
+                tokio::time::sleep(std::time::Duration::from_micros(RETRY_BACKOFFS_US[attempt])).await; // Backoff curto.
+                attempt += 1;
+            }
+        }
+    }
+}
 
 /* ============================== HELPERS DE RESPOSTA ====================================
    Pequenas funções utilitárias para padronizar respostas HTTP JSON e respostas vazias. */
@@ -186,28 +597,43 @@ async fn health() -> Response {
 
 /* ============================== HANDLER: GET /extrato ==================================
    Recebe o id do cliente via Path e busca o extrato no banco. Regras:
-   - Aceita apenas ids de 1 a 5 (checagem rápida com aritmética).
+   - Aceita apenas ids dentro de `cfg.min_account_id..=cfg.max_account_id`.
    - Se o banco retornar JSON, responde 200; se NULL, responde 404; se erro de banco, 500.
 
-   Detalhe da checagem com wrapping_sub:
-   - O teste "uid.wrapping_sub(1) > 4" é uma forma branch-friendly de verificar 1..=5 sem
-     escrever duas comparações (id >= 1 && id <= 5). Para valores 1..=5, a expressão é falsa. */
-async fn get_extrato(State(pool): State<PgPool>, Path(id): Path<u8>) -> Response {
-    let uid = id as u32;                       // Converte para u32 para aplicar a checagem numérica barata.
-    if uid.wrapping_sub(1) > 4 {               // Aceita apenas 1..=5: para esses valores a expressão é falsa.
-        return empty(StatusCode::NOT_FOUND);   // Fora do intervalo esperado → 404 (cliente inexistente).
+   Instrumentação: esta função delega para get_extrato_inner e só cuida de medir latência,
+   contar o status final e manter o gauge de requisições em andamento — assim a lógica de
+   negócio continua isolada e fácil de ler. */
+async fn get_extrato(
+    State(pool): State<PgPool>,
+    State(cfg): State<Arc<Config>>,
+    State(metrics): State<Arc<Metrics>>,
+    Path(id): Path<u8>,
+) -> Response {
+    let _in_flight = InFlightGuard::new(metrics.requests_in_flight.clone()); // Conta esta requisição como "em andamento" até o fim do escopo.
+    let start = Instant::now();                 // Marca o início para medir a latência do handler.
+
+    let response = get_extrato_inner(&pool, &cfg, id).await; // Roda a lógica de negócio de fato.
+
+    metrics.record("/clientes/:id/extrato", response.status(), start.elapsed()); // Latência + contador por (rota, status).
+
+    response
+}
+
+async fn get_extrato_inner(pool: &PgPool, cfg: &Config, id: u8) -> Response {
+    if !cfg.account_id_in_range(id) {          // Fora da faixa configurada → 404 (cliente inexistente).
+        return empty(StatusCode::NOT_FOUND);
     }
 
     // Consulta escalar que devolve Option<String>: Some(JSON) se existir, None se não houver conta.
     match sqlx::query_scalar::<Postgres, Option<String>>(Q_GET_EXTRATO)
         .persistent(true)                      // Sinaliza uso de prepared statement persistente (melhor sob carga).
         .bind(id as i32)                       // Passa o parâmetro da função SQL (p_account_id).
-        .fetch_one(&pool)                      // Executa no pool de conexões com o Postgres.
+        .fetch_one(pool)                       // Executa no pool de conexões com o Postgres.
         .await
     {
         Ok(Some(body)) => json_text(StatusCode::OK, body),    // Conta existe: responde 200 com o JSON do banco.
         Ok(None) => empty(StatusCode::NOT_FOUND),             // Conta não existe: 404 sem corpo.
-        Err(_) => empty(StatusCode::INTERNAL_SERVER_ERROR),   // Falha de banco: 500 sem detalhes (teste sintético).
+        Err(err) => empty(classify(&err).0),                  // Falha de banco: status conforme o SQLSTATE (ver `classify`).
     }
 }
 
@@ -219,15 +645,37 @@ async fn get_extrato(State(pool): State<PgPool>, Path(id): Path<u8>) -> Response
 
    Observação sobre validação automática do Axum:
    - Se o JSON for malformado ou não bater com o schema de TxPayload, o extractor Json<T>
-     já responde 400 Bad Request antes mesmo de o handler rodar. */
+     já responde 400 Bad Request antes mesmo de o handler rodar.
+
+   Instrumentação: mesmo esquema do get_extrato — post_transacao só mede latência, conta o
+   status final e delega a regra de negócio para post_transacao_inner. */
 async fn post_transacao(
-    State(pool): State<PgPool>,                // Injeta o pool de conexões no handler.
-    Path(id): Path<u8>,                        // Extrai {id} da URL como u8 (suficiente para 1..=5).
-    Json(payload): Json<TxPayload>,            // Desserializa o corpo JSON em TxPayload.
+    State(pool): State<PgPool>,
+    State(cfg): State<Arc<Config>>,
+    State(metrics): State<Arc<Metrics>>,
+    Path(id): Path<u8>,
+    headers: HeaderMap,
+    Json(payload): Json<TxPayload>,
+) -> Response {
+    let _in_flight = InFlightGuard::new(metrics.requests_in_flight.clone()); // Conta esta requisição como "em andamento" até o fim do escopo.
+    let start = Instant::now();                 // Marca o início para medir a latência do handler.
+
+    let response = post_transacao_inner(&pool, &cfg, id, headers, payload).await; // Roda a lógica de negócio de fato.
+
+    metrics.record("/clientes/:id/transacoes", response.status(), start.elapsed()); // Latência + contador por (rota, status).
+
+    response
+}
+
+async fn post_transacao_inner(
+    pool: &PgPool,                             // Pool de conexões com o Postgres.
+    cfg: &Config,                              // Faixa de ids válidos e demais políticas configuráveis.
+    id: u8,                                    // Id do cliente extraído da URL.
+    headers: HeaderMap,                        // Dá acesso ao cabeçalho opcional Idempotency-Key.
+    payload: TxPayload,                        // Corpo JSON já desserializado.
 ) -> Response {
-    let uid = id as u32;                       // Converte para u32 para a mesma checagem barata.
-    if uid.wrapping_sub(1) > 4 {               // Apenas ids 1..=5 são aceitos no cenário do teste.
-        return empty(StatusCode::NOT_FOUND);   // Qualquer outro id retorna 404.
+    if !cfg.account_id_in_range(id) {          // Fora da faixa configurada → 404.
+        return empty(StatusCode::NOT_FOUND);
     }
 
     let v = payload.valor;                     // Lê o valor informado no JSON.
@@ -246,23 +694,116 @@ async fn post_transacao(
         _ => return empty(StatusCode::UNPROCESSABLE_ENTITY), // Qualquer outro caractere → 422.
     };
 
-    // Executa a função process_transaction e trata retorno especial com {"error":1}.
-    match sqlx::query_scalar::<Postgres, String>(Q_PROCESS_TX)
-        .persistent(true)                      // Prepared statement persistente ajuda em cenários de alta repetição.
+    // Se o cliente mandou Idempotency-Key, usamos a variante idempotente (que grava/confere a
+    // chave dentro da mesma transação de banco); senão, o caminho de sempre.
+    let idempotency_key = headers
+        .get("Idempotency-Key")
+        .and_then(|value| value.to_str().ok());
+
+    // Executa process_transaction (com retry automático em erro transitório) e trata o
+    // retorno especial {"error":1}.
+    let result = match idempotency_key {
+        Some(key) => exec_process_transaction_idempotent(pool, id as i32, v as i32, tipo, &payload.descricao, key).await,
+        None => exec_process_transaction(pool, id as i32, v as i32, tipo, &payload.descricao).await,
+    };
+
+    match result {
+        Ok(body) if body.contains("\"error\": 2") => empty(StatusCode::CONFLICT), // Idempotency-Key reaproveitada em outra conta.
+        Ok(body) if body.contains("\"error\"") => empty(StatusCode::UNPROCESSABLE_ENTITY), // Negócio inválido (ex.: limite).
+        Ok(body) => json_text(StatusCode::OK, body), // Sucesso (ou replay idempotente): retorna 200 com JSON vindo do banco.
+        Err(err) => empty(classify(&err).0),         // Falha de banco: status conforme o SQLSTATE (ver `classify`).
+    }
+
+    // Nota: process_transaction/process_transactions só retornam {"error":1} (negócio inválido)
+    // ou {"saldo":...,"limite":...} (process_transactions acrescenta "resultados");
+    // process_transaction_idempotent também pode retornar
+    // {"error":2} quando a Idempotency-Key já foi usada por outra conta (ver checagem acima).
+}
+
+/* ============================== HANDLER: POST /transacoes/lote =========================
+   Recebe id e um array JSON de TxPayload, aplicando todas as transações de uma vez contra
+   a mesma conta. Regras:
+   - id deve estar dentro de `cfg.min_account_id..=cfg.max_account_id` (como nos demais handlers).
+   - Lote vazio é rejeitado (nada para aplicar).
+   - Cada item é validado com as MESMAS regras baratas do endpoint individual (valor>0,
+     descricao 1..=10 bytes, tipo c/d); qualquer item inválido rejeita o lote inteiro com
+     422 antes de tocar no banco — a rejeição "parcial" (débito que estoura o limite) é uma
+     decisão de negócio e só acontece dentro da função SQL, item a item.
+   - Em caso de sucesso, repassa o JSON de process_transactions (saldo, limite, resultados). */
+async fn post_transacoes_lote(
+    State(pool): State<PgPool>,                // Injeta o pool de conexões no handler.
+    State(cfg): State<Arc<Config>>,            // Faixa de ids válidos e demais políticas configuráveis.
+    Path(id): Path<u8>,                        // Extrai {id} da URL como u8.
+    Json(payloads): Json<Vec<TxPayload>>,      // Desserializa o corpo JSON em um array de TxPayload.
+) -> Response {
+    if !cfg.account_id_in_range(id) {          // Fora da faixa configurada → 404.
+        return empty(StatusCode::NOT_FOUND);
+    }
+
+    if payloads.is_empty() {                  // Lote vazio não tem o que aplicar.
+        return empty(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    // Monta os três arrays paralelos que serão enviados ao Postgres via unnest().
+    let mut valores: Vec<i32> = Vec::with_capacity(payloads.len());
+    let mut tipos: Vec<String> = Vec::with_capacity(payloads.len());
+    let mut descricoes: Vec<String> = Vec::with_capacity(payloads.len());
+
+    for item in &payloads {                    // Valida cada item antes de montar os arrays.
+        if item.valor == 0 {                   // Rejeita valores não positivos (precisa ser > 0).
+            return empty(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let dlen = item.descricao.len();       // Mede o tamanho em bytes da descrição.
+        if dlen == 0 || dlen > 10 {             // Exige entre 1 e 10 bytes (regra do desafio).
+            return empty(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        match item.tipo {                      // Converte o char em String de 1 byte aceita pelo cast ::char[].
+            'c' => tipos.push("c".to_string()),
+            'd' => tipos.push("d".to_string()),
+            _ => return empty(StatusCode::UNPROCESSABLE_ENTITY), // Qualquer outro caractere → 422.
+        }
+
+        valores.push(item.valor as i32);       // Postgres usa INT; convertendo de u32 para i32.
+        descricoes.push(item.descricao.clone()); // Tamanho já validado acima.
+    }
+
+    // Executa process_transactions com os arrays ligados via bind (SQLx codifica Vec<T> como array[]).
+    match sqlx::query_scalar::<Postgres, String>(Q_PROCESS_TX_LOTE)
+        .persistent(true)                      // Prepared statement persistente ajuda sob carga repetida.
         .bind(id as i32)                       // p_account_id.
-        .bind(v as i32)                        // p_amount (Postgres usa INT; convertendo de u32 para i32).
-        .bind(tipo)                            // p_type ('c' ou 'd').
-        .bind(&payload.descricao)              // p_description (tamanho já validado).
+        .bind(&valores)                        // p_valores (int[]).
+        .bind(&tipos)                          // p_tipos (char[], via cast explícito na query).
+        .bind(&descricoes)                     // p_descricoes (text[]).
         .fetch_one(&pool)                      // Executa e coleta a string JSON.
         .await
     {
-        Ok(body) if body.contains("\"error\"") => empty(StatusCode::UNPROCESSABLE_ENTITY), // Negócio inválido (ex.: limite).
-        Ok(body) => json_text(StatusCode::OK, body), // Sucesso: retorna 200 com JSON vindo do banco.
-        Err(_) => empty(StatusCode::INTERNAL_SERVER_ERROR), // Qualquer falha inesperada de banco → 500.
+        Ok(body) if body.contains("\"error\"") => empty(StatusCode::UNPROCESSABLE_ENTITY), // Conta inexistente.
+        Ok(body) => json_text(StatusCode::OK, body), // Sucesso: retorna 200 com o JSON do banco.
+        Err(err) => empty(classify(&err).0), // Falha de banco: status conforme o SQLSTATE (ver `classify`).
     }
+}
+
+/* ============================== HANDLER: GET /metrics ===================================
+   Expõe as métricas acima no formato texto do Prometheus. Antes de serializar, amostra o
+   estado atual do PgPool (tamanho total e conexões ociosas) nas gauges correspondentes —
+   assim cada scrape reflete o pool no exato instante da consulta, sem precisar de um
+   background task separado só para isso. */
+async fn metrics_handler(State(pool): State<PgPool>, State(metrics): State<Arc<Metrics>>) -> Response {
+    metrics.db_pool_size.set(pool.size() as i64);      // Conexões abertas (em uso + ociosas) neste instante.
+    metrics.db_pool_idle.set(pool.num_idle() as i64);  // Conexões ociosas, prontas para reuso imediato.
 
-    // Nota: o teste com .contains("\"error\"") é simples e suficiente aqui pois a função
-    // process_transaction só retorna dois formatos: {"error":1} ou {"saldo":...,"limite":...}.
+    let metric_families = metrics.registry.gather(); // Coleta todas as métricas do registry do AppState.
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    encoder.encode(&metric_families, &mut buffer).unwrap(); // Serializa no formato texto do Prometheus.
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, encoder.format_type())         // Prometheus exige um Content-Type próprio (text/plain; version=...).
+        .body(Body::from(buffer))
+        .unwrap()
 }
 
 /* ============================== FUNÇÃO MAIN (INICIALIZAÇÃO) =============================
@@ -278,30 +819,60 @@ async fn post_transacao(
    - synchronous_commit='off' pode perder transações nos últimos milissegundos em panes; use
      com cuidado e apenas quando a durabilidade imediata não for requisito (benchmarks).
    - PG_MIN/PG_MAX calibram o pool; exagerar no máximo pode aumentar contenção no Postgres.
+   - DB_HOSTADDR (opcional): quando presente e for um literal IPv4/IPv6 válido, é usado como o
+     host efetivo de conexão, evitando a resolução de DNS a cada nova conexão do pool. O
+     `PgConnectOptions` desta versão do SQLx não expõe um campo `hostaddr` separado de `host`
+     (como o libpq faz); o mesmo valor de `host(...)` é usado tanto para abrir o socket quanto
+     para TLS/SNI, então não dá para manter DB_HOST como nome "lógico" e ainda assim pular a
+     resolução — se DB_HOSTADDR for fornecido, ele substitui DB_HOST por inteiro nesta conexão.
+     Essa é uma troca deliberada (perde-se o casamento por nome em pg_hba/SNI quando os dois
+     divergem) aceita aqui porque o cenário de uso é conectar a um IP de banco já confiável
+     dentro da mesma rede, não a um host público atrás de TLS com SNI.
    - 0.0.0.0 expõe o serviço na rede; para uso local, 127.0.0.1 é suficiente. */
 #[tokio::main]                                 // Macro que inicializa o runtime assíncrono do Tokio (multi-thread por padrão).
 async fn main() -> anyhow::Result<()> {        // Retorna Result para poder usar ? na inicialização.
     let db_host = std::env::var("DB_HOST").unwrap_or_else(|_| "localhost".to_string()); // Lê host do banco ou usa "localhost".
-    let db_port = std::env::var("DB_PORT").unwrap_or_else(|_| "5432".to_string());      // Porta padrão do Postgres é 5432.
+    let db_hostaddr = std::env::var("DB_HOSTADDR").ok();                               // Endereço IP numérico opcional, para pular o DNS.
+    let db_port: u16 = std::env::var("DB_PORT").ok().and_then(|s| s.parse().ok()).unwrap_or(5432); // Porta padrão do Postgres é 5432.
     let db_user = std::env::var("DB_USER").unwrap_or_else(|_| "postgres".to_string());  // Usuário padrão (ajuste em produção).
     let db_password = std::env::var("DB_PASSWORD").unwrap_or_else(|_| "postgres".to_string()); // Senha padrão (apenas para teste).
     let db_database = std::env::var("DB_DATABASE").unwrap_or_else(|_| "postgres_api_db".to_string()); // Nome do DB.
-    let min_conns: u32 = std::env::var("PG_MIN").ok().and_then(|s| s.parse().ok()).unwrap_or(5);  // Tamanho mínimo do pool.
-    let max_conns: u32 = std::env::var("PG_MAX").ok().and_then(|s| s.parse().ok()).unwrap_or(30); // Tamanho máximo do pool.
-    let database_url = format!("postgres://{}:{}@{}:{}/{}", db_user, db_password, db_host, db_port, db_database); // Monta URL.
 
+    // Se DB_HOSTADDR estiver definido, ele precisa ser um literal numérico válido e vira o host
+    // de conexão (sem DNS); se não for um IP, a inicialização aborta com panic. Sem DB_HOSTADDR,
+    // usamos DB_HOST (que o resolver precisa traduzir).
+    let connect_host = match db_hostaddr.as_deref() {
+        Some(hostaddr) => {
+            hostaddr.parse::<std::net::IpAddr>().expect("DB_HOSTADDR deve ser um endereço IPv4/IPv6 numérico válido");
+            hostaddr
+        }
+        None => db_host.as_str(),
+    };
+
+    let cfg = Arc::new(Config::from_env());    // Faixa de ids válidos, tamanho do pool e modo de synchronous_commit.
+    let metrics = Arc::new(Metrics::new());    // Handle de métricas Prometheus compartilhado via AppState.
+
+    let connect_options = PgConnectOptions::new()
+        .host(connect_host)
+        .port(db_port)
+        .username(&db_user)
+        .password(&db_password)
+        .database(&db_database);
+
+    let synchronous_commit = cfg.synchronous_commit.clone(); // Clonado para entrar no closure 'static do after_connect.
     let pool = PgPoolOptions::new()            // Constrói opções do pool de conexões do SQLx.
-        .min_connections(min_conns)            // Define mínimo de conexões abertas.
-        .max_connections(max_conns)            // Define máximo para limitar consumo e contenção.
-        .after_connect(|conn, _meta| {         // Callback executado a cada conexão recém-criada no pool.
+        .min_connections(cfg.pg_min_conns)     // Define mínimo de conexões abertas (vem de Config).
+        .max_connections(cfg.pg_max_conns)     // Define máximo para limitar consumo e contenção (vem de Config).
+        .after_connect(move |conn, _meta| {    // Callback executado a cada conexão recém-criada no pool.
+            let synchronous_commit = synchronous_commit.clone();
             Box::pin(async move {
-                sqlx::query("SET synchronous_commit = 'off'") // Ajuste de sessão: melhora latência/throughput em benchmarks,
+                sqlx::query(&format!("SET synchronous_commit = '{synchronous_commit}'")) // Ajuste de sessão: melhora latência/throughput em benchmarks,
                     .execute(&mut *conn)                      // abrindo mão de durabilidade imediata dos últimos ms.
                     .await?;
                 Ok::<_, sqlx::Error>(())
             })
         })
-        .connect(&database_url)                // Abre conexões ao banco.
+        .connect_with(connect_options)         // Abre conexões ao banco usando connect_host (DB_HOSTADDR ou DB_HOST).
         .await?;                               // Espera a criação do pool (pode falhar se o banco estiver indisponível).
 
     // Observação: estas execuções são idempotentes (CREATE OR REPLACE / IF NOT EXISTS).
@@ -309,12 +880,19 @@ async fn main() -> anyhow::Result<()> {        // Retorna Result para poder usar
     sqlx::query(CREATE_INDEX_SQL).execute(&pool).await?;              // Garante a existência do índice (idempotente).
     sqlx::query(CREATE_EXTRACT_FUNCTION_SQL).execute(&pool).await?;   // Instala/atualiza função get_extrato.
     sqlx::query(CREATE_TRANSACTION_FUNCTION_SQL).execute(&pool).await?; // Instala/atualiza função process_transaction.
+    sqlx::query(CREATE_TRANSACTIONS_BATCH_FUNCTION_SQL).execute(&pool).await?; // Instala/atualiza função process_transactions (lote).
+    sqlx::query(CREATE_IDEMPOTENCY_KEYS_TABLE_SQL).execute(&pool).await?;  // Garante a existência da tabela idempotency_keys.
+    sqlx::query(CREATE_IDEMPOTENT_TRANSACTION_FUNCTION_SQL).execute(&pool).await?; // Instala/atualiza função process_transaction_idempotent.
+
+    let state = AppState { pool, cfg, metrics }; // Agrupa pool, config e métricas num único State clonável.
 
     let app = Router::new()                                           // Cria o roteador principal da API.
         .route("/health", get(health))                                // Registra rota GET de health.
+        .route("/metrics", get(metrics_handler))                      // Registra rota GET de métricas Prometheus.
         .route("/clientes/{id}/extrato", get(get_extrato))            // Registra rota GET de extrato.
         .route("/clientes/{id}/transacoes", post(post_transacao))     // Registra rota POST de transações.
-        .with_state(pool);                                            // Anexa o pool como estado compartilhado para os handlers.
+        .route("/clientes/{id}/transacoes/lote", post(post_transacoes_lote)) // Registra rota POST de lote de transações.
+        .with_state(state);                                           // Anexa o AppState compartilhado para os handlers.
 
     let port: u16 = std::env::var("PORT").ok().and_then(|s| s.parse().ok()).unwrap_or(8080); // Porta configurável (padrão 8080).
     let addr: SocketAddr = ([0, 0, 0, 0], port).into();                                      // 0.0.0.0 expõe para outras máquinas na rede.
@@ -332,3 +910,62 @@ async fn main() -> anyhow::Result<()> {        // Retorna Result para poder usar
 
     Ok(())                                    // Final feliz da função main.
 }
+
+/* ============================== TESTES: Config ==========================================
+   Config::account_id_in_range e Config::validate_synchronous_commit são funções puras (sem
+   I/O, sem banco), extraídas justamente para tirar números/strings mágicas dos handlers — o
+   que as torna triviais de testar isoladamente. validate_synchronous_commit é também a defesa
+   contra SQL injection na interpolação de `SET synchronous_commit = '...'`, então a lista de
+   valores aceitos fica travada aqui como regressão. */
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    fn cfg_with_range(min_account_id: u8, max_account_id: u8) -> Config {
+        Config {
+            min_account_id,
+            max_account_id,
+            pg_min_conns: 5,
+            pg_max_conns: 30,
+            synchronous_commit: "off".to_string(),
+        }
+    }
+
+    #[test]
+    fn account_id_in_range_aceita_os_limites_inclusive() {
+        let cfg = cfg_with_range(1, 5);
+        assert!(cfg.account_id_in_range(1));
+        assert!(cfg.account_id_in_range(5));
+        assert!(cfg.account_id_in_range(3));
+    }
+
+    #[test]
+    fn account_id_in_range_rejeita_fora_dos_limites() {
+        let cfg = cfg_with_range(1, 5);
+        assert!(!cfg.account_id_in_range(0));
+        assert!(!cfg.account_id_in_range(6));
+    }
+
+    #[test]
+    fn account_id_in_range_respeita_faixa_customizada() {
+        let cfg = cfg_with_range(10, 12);
+        assert!(!cfg.account_id_in_range(9));
+        assert!(cfg.account_id_in_range(10));
+        assert!(cfg.account_id_in_range(12));
+        assert!(!cfg.account_id_in_range(13));
+    }
+
+    #[test]
+    fn validate_synchronous_commit_aceita_valores_conhecidos_do_postgres() {
+        for valor in ["on", "off", "local", "remote_write", "remote_apply"] {
+            assert_eq!(Config::validate_synchronous_commit(valor.to_string()), valor);
+        }
+    }
+
+    #[test]
+    fn validate_synchronous_commit_rejeita_valores_desconhecidos_e_cai_para_off() {
+        assert_eq!(Config::validate_synchronous_commit("off'; DROP TABLE accounts; --".to_string()), "off");
+        assert_eq!(Config::validate_synchronous_commit("".to_string()), "off");
+        assert_eq!(Config::validate_synchronous_commit("ON".to_string()), "off"); // case-sensitive: Postgres espera minúsculo.
+    }
+}